@@ -14,6 +14,7 @@ use core::marker::PhantomData;
 use core::hash::Hash;
 use core::cmp::{min,max,Ord,Ordering};
 use core::ops::Index;
+use core::fmt::{self,Display,Formatter};
 
 use bincode::{Encode,Decode,BorrowDecode};
 use bincode::enc::{Encoder};
@@ -23,9 +24,15 @@ use bincode::error::{EncodeError,DecodeError};
 use bincode::enc::write::Writer;
 
 use std::borrow::Cow;
-use std::io::{Read,Error,ErrorKind,BufWriter,Write};
+use std::io::{Read,Error,ErrorKind,BufWriter,Write,IoSlice};
 use std::fs::{File,OpenOptions};
 use std::path::Path;
+use std::ops::Deref;
+use std::sync::mpsc::{sync_channel,SyncSender};
+use std::thread::JoinHandle;
+
+use memmap2::Mmap;
+use xxhash_rust::xxh64::Xxh64;
 
 type Locator = u32;
 type Plan = Locator;
@@ -162,6 +169,8 @@ pub struct CompressedMap<'a,K,V> {
     response_map: ResponseMap<V>,
     salt: Vec<u8>,
     core: Vec<MapCore<'a>>,
+    /** Whether the serialized response-value region should be LZ4-compressed. */
+    compress_values: bool,
     _phantom: PhantomData<K>
 }
 
@@ -172,6 +181,7 @@ impl <'a,K,V> Clone for CompressedMap<'a,K,V> where V:Clone {
             response_map: self.response_map.clone(),
             salt: self.salt.clone(),
             core: self.core.clone(),
+            compress_values: self.compress_values,
             _phantom: PhantomData::default()
         }
     }
@@ -216,6 +226,7 @@ impl <'a,K:Hash,V> CompressedMap<'a,K,V> {
                 response_map: vec![(0,(*v).clone())],
                 salt: vec![],
                 core: vec![],
+                compress_values: false,
                 _phantom: PhantomData::default()
             });
         }
@@ -360,10 +371,115 @@ impl <'a,K:Hash,V> CompressedMap<'a,K,V> {
             response_map: response_map,
             salt: salt[1..nphases].to_vec(),
             core: core,
+            compress_values: false,
             _phantom: PhantomData::default()
         })
     }
 
+    /**
+     * Request LZ4 compression of the serialized response-value region.
+     *
+     * The entropy-coded core blocks are incompressible and are always stored
+     * raw, but the `V` values can be large and redundant.  When this is set and
+     * the `lz4` feature is enabled, [`Encode`]/[`write_to`](Self::write_to)
+     * compress just that region with [`lz4_flex`], recording a format bit so
+     * the decoder knows to decompress.  Compression is skipped automatically if
+     * the compressed payload is not smaller.  With the `lz4` feature disabled
+     * this flag has no effect.
+     *
+     * The flag describes the *serialized form*, not the logical contents: when
+     * compression is skipped because it wouldn't shrink, the encoded bytes carry
+     * no `FLAG_LZ4`, so a map decoded from them reports `compress_values ==
+     * false` even though the original requested `true`.  That field is the only
+     * one that can differ across such a round-trip; the keys, values and query
+     * results are identical.
+     */
+    pub fn compress_values(mut self, yes: bool) -> Self {
+        self.compress_values = yes;
+        self
+    }
+
+    /**
+     * Describe the internal layout of a built map.
+     *
+     * Returns a [`MapStats`] report: how [`formulate_plan`] laid out the phases
+     * (per-phase shift and bit count), the size of each core, the response
+     * intervals with their widths and implied probabilities, the total
+     * serialized size, and a bits-per-key figure against the Shannon entropy
+     * of those implied probabilities (the rounded interval widths, not the
+     * exact input value counts).  A built map no longer carries the input key
+     * count, so `estimated_keys` reports the widest phase's block-slot
+     * *capacity* — an upper bound on the true population — which makes the
+     * derived `bits_per_key` a lower bound.  This is a diagnostic aid for
+     * spotting pathological value distributions; it does not crack open the
+     * cores.
+     */
+    pub fn describe(&self) -> MapStats where V: Encode {
+        let mut phases = Vec::with_capacity(self.core.len());
+        let mut cur = self.plan;
+        for core in &self.core {
+            let next = cur & cur.wrapping_sub(1);
+            let shift = cur.trailing_zeros();
+            let nbits = next.trailing_zeros() - cur.trailing_zeros();
+            phases.push(PhaseStats {
+                shift,
+                nbits,
+                nblocks: core.nblocks,
+                bytes: core.blocks.as_ref().len()
+            });
+            cur = next;
+        }
+
+        /* Response intervals, widths and probabilities. */
+        let n = self.response_map.len();
+        let mut intervals = Vec::with_capacity(n);
+        let mut entropy = 0.0f64;
+        const SPACE : f64 = (1u64 << Locator::BITS) as f64;
+        for i in 0..n {
+            let lo = self.response_map[i].0;
+            let (width,probability) = if n == 1 {
+                (1u64 << Locator::BITS, 1.0)
+            } else {
+                let w = if i+1 < n {
+                    self.response_map[i+1].0 - lo
+                } else {
+                    lo.wrapping_neg()
+                };
+                (w as u64, w as f64 / SPACE)
+            };
+            if probability > 0.0 { entropy -= probability * probability.log2(); }
+            intervals.push(IntervalStats { value_index: i, lo, width, probability });
+        }
+
+        /* The widest phase has a slot for every key plus the solver's headroom,
+         * so its block-slot capacity is an *upper bound* on the key population
+         * (the solver runs below a full load factor).  Each block holds
+         * `BLOCKSIZE` bytes, i.e. `8*BLOCKSIZE` one-bit slots, per value bit.
+         * We don't keep the input count after building, so this capacity is
+         * the best proxy we have. */
+        let capacity_keys = phases.iter().map(|p| p.nblocks * BLOCKSIZE * 8).max().unwrap_or(0);
+        let core_bytes : usize = phases.iter().map(|p| p.bytes).sum();
+        let serialized_bytes = bincode::encode_to_vec(self, STD_BINCODE_CONFIG)
+            .map(|v| v.len()).unwrap_or(0);
+        /* Dividing by a capacity that overcounts keys makes this a *lower*
+         * bound on the true bits-per-key. */
+        let bits_per_key = if capacity_keys == 0 {
+            0.0
+        } else {
+            (core_bytes * 8) as f64 / capacity_keys as f64
+        };
+
+        MapStats {
+            phases,
+            intervals,
+            entropy,
+            estimated_keys: capacity_keys,
+            core_bytes,
+            serialized_bytes,
+            bits_per_key
+        }
+    }
+
     fn bsearch<'b>(&'b self, low: Locator, high: Locator) -> Option<&'b V> {
         let plow  = self.response_map.partition_point(|(begin,_v)| *begin <= low) - 1;
         if (plow == self.response_map.len() - 1)
@@ -426,6 +542,7 @@ impl <'a,K:Hash,V> CompressedMap<'a,K,V> {
             response_map: self.response_map,
             salt: self.salt,
             core: self.core.into_iter().map(|c| c.take_ownership()).collect(),
+            compress_values: self.compress_values,
             _phantom: PhantomData::default()
         }
     }
@@ -440,15 +557,45 @@ impl <'a,K:Hash,V> CompressedMap<'a,K,V> {
     where V: Encode {
         let file = OpenOptions::new().create_new(true).write(true).open(path)?;
         let mut writer = BufWriter::new(file);
-        bincode::encode_into_std_write(self, &mut writer, STD_BINCODE_CONFIG).map_err(
-            |e| match e {
-                EncodeError::Io{ error:e, index:_s } => e,
-                EncodeError::Other(s) => Error::new(ErrorKind::Other, s),
-                _ => Error::new(ErrorKind::Other, e.to_string()),
-        })?;
+        self.write_to(&mut writer)?;
         writer.flush()
     }
 
+    /**
+     * Serialize the map to a [`Write`] without buffering the entropy-coded payload.
+     *
+     * The byte layout is identical to the [`Encode`] implementation, so files
+     * written this way are interchangeable with [`encode_to_vec`](bincode::encode_to_vec)
+     * output.  Unlike [`write_to_file`](Self::write_to_file)'s [`BufWriter`], the
+     * large `core[i].blocks` slices are never copied through an intermediate
+     * buffer: the small header/response/nblocks prefix is emitted first, then
+     * every core's contiguous block slice is handed to the writer in one
+     * gathered [`write_vectored`](Write::write_vectored) pass.  Writers without
+     * real vectored support degrade gracefully to sequential writes.
+     */
+    pub fn write_to<W: Write>(&self, w: &mut W) -> Result<(), Error>
+    where V: Encode {
+        /* Emit the body prefix (everything but magic/digest and block payloads)
+         * into a small buffer, then checksum the whole body.
+         */
+        let mut prefix = Vec::new();
+        bincode::encode_into_std_write(EncodePrefix(self), &mut prefix, STD_BINCODE_CONFIG)
+            .map_err(encode_io_error)?;
+        let digest = body_digest(&prefix, &self.core);
+
+        /* Magic and digest go out first, then the body gathered vectored so the
+         * large block payloads are never copied.
+         */
+        w.write_all(MAGIC2)?;
+        w.write_all(&digest.to_le_bytes())?;
+        let mut bufs : Vec<&[u8]> = Vec::with_capacity(self.core.len()+1);
+        bufs.push(&prefix);
+        for core in &self.core {
+            bufs.push(core.blocks.as_ref());
+        }
+        write_all_vectored(w, &bufs)
+    }
+
     /**
      * Read a map from a file.
      *
@@ -470,37 +617,501 @@ impl <'a,K:Hash,V> CompressedMap<'a,K,V> {
             Ok(unowned.take_ownership())
         }
     }
+
+    /**
+     * Serialize to a writer through a background thread.
+     *
+     * Wraps `w` in a [`BackgroundWriter`] so the actual `write` syscalls run on
+     * a dedicated thread fed over a bounded channel, letting serialization
+     * compute overlap with I/O.  The byte layout is identical to
+     * [`write_to`](Self::write_to); this just changes who does the writing.
+     */
+    pub fn write_to_background<W: Write + Send + 'static>(&self, w: W, depth: usize)
+        -> Result<(), Error>
+    where V: Encode {
+        let mut bg = BackgroundWriter::new(w, depth);
+        self.write_to(&mut bg)?;
+        bg.finish()
+    }
+
+    /**
+     * Reconstruct a map from a reader, section by section.
+     *
+     * Unlike [`read_from_file`](Self::read_from_file), which slurps the whole
+     * input into a [`Vec<u8>`] before decoding, this reads the small
+     * header/response/nblocks prefix incrementally and then pulls each core's
+     * block section straight into owned memory, so peak memory never holds a
+     * second copy of the entropy-coded payload.  Accepts both `cnm1` and `cnm2`
+     * containers and verifies the `cnm2` checksum.
+     */
+    pub fn read_from<R: Read>(mut r: R) -> Result<Self, Error>
+    where V: Decode {
+        fn corrupt(descr: &str) -> Error { Error::new(ErrorKind::Other, descr.to_string()) }
+
+        let mut magic = [0u8;4];
+        r.read_exact(&mut magic)?;
+        let checksummed = if &magic == MAGIC2 {
+            true
+        } else if &magic == MAGIC {
+            false
+        } else {
+            return Err(corrupt("magic value mismatch"));
+        };
+
+        let stored_digest = if checksummed {
+            let mut raw = [0u8;8];
+            r.read_exact(&mut raw)?;
+            Some(u64::from_le_bytes(raw))
+        } else {
+            None
+        };
+
+        let flags : u8 = if checksummed { dread(&mut r)? } else { 0 };
+        let compressed = (flags & FLAG_LZ4) != 0;
+
+        let log_responses : Vec<u8> = dread(&mut r)?;
+        let nresp = log_responses.len()+1;
+        let mut responses : Vec<V> = Vec::with_capacity(nresp);
+        if compressed {
+            #[cfg(feature = "lz4")]
+            {
+                let frame : Vec<u8> = dread(&mut r)?;
+                let raw = decompress_values(&frame).map_err(|e| corrupt(&e.to_string()))?;
+                let mut off = 0;
+                for _ in 0..nresp {
+                    let (v,used) : (V,usize) = bincode::decode_from_slice(&raw[off..], STD_BINCODE_CONFIG)
+                        .map_err(decode_io_error)?;
+                    responses.push(v);
+                    off += used;
+                }
+            }
+            #[cfg(not(feature = "lz4"))]
+            return Err(corrupt("value region is LZ4-compressed but the lz4 feature is not enabled"));
+        } else {
+            for _ in 0..nresp { responses.push(dread(&mut r)?); }
+        }
+
+        /* Rebuild the response map (same shape as borrow_decode). */
+        let mut response_map = Vec::with_capacity(responses.len());
+        let mut total : Locator = 0;
+        for (i,response) in responses.into_iter().enumerate() {
+            if i < log_responses.len() {
+                let logr = log_responses[i] as u32;
+                if logr == 0 || logr > Locator::BITS { return Err(corrupt("invalid logr")); }
+                let r = 1 << (Locator::BITS - logr);
+                response_map.push((total,response));
+                total = total.checked_add(r).ok_or(corrupt("responses must sum to < Locator::BITS"))?;
+            } else {
+                response_map.push((total,response));
+            }
+        }
+
+        let hash_key : [u8;16] = dread(&mut r)?;
+        let plan : Locator = dread(&mut r)?;
+        let nphases = plan.count_ones() as usize;
+
+        let len_salt = max(1,nphases)-1;
+        let mut salt = vec![0u8;len_salt];
+        r.read_exact(&mut salt)?;
+        let mut nblocks_per_phase : Vec<usize> = Vec::with_capacity(nphases);
+        for _ in 0..nphases {
+            let nblocks : usize = dread(&mut r)?;
+            if nblocks < 2 { return Err(corrupt("must have at least 2 nblocks")); }
+            nblocks_per_phase.push(nblocks);
+        }
+
+        /* Pull each core's blocks straight into owned memory, one section at a time. */
+        let mut core : Vec<MapCore> = Vec::with_capacity(nphases);
+        let mut hashcur = hash_key;
+        let mut cur_plan = plan;
+        for phase in 0..nphases {
+            let nblocks = nblocks_per_phase[phase];
+            let next_plan = cur_plan & (cur_plan-1);
+            let bpv = next_plan.trailing_zeros() - cur_plan.trailing_zeros();
+            cur_plan = next_plan;
+            let len = nblocks.checked_mul(BLOCKSIZE)
+                .and_then(|x| x.checked_mul(bpv as usize))
+                .ok_or(corrupt("overflow on multiply"))?;
+            let mut blocks = vec![0u8; len];
+            r.read_exact(&mut blocks)?;
+            core.push(MapCore {
+                hash_key: hashcur,
+                bits_per_value: bpv as u8,
+                nblocks,
+                blocks: Cow::Owned(blocks)
+            });
+            if phase < salt.len() {
+                hashcur = choose_key(Some(hashcur), salt[phase] as usize);
+            }
+        }
+
+        let result = CompressedMap {
+            plan,
+            response_map,
+            salt,
+            core,
+            compress_values: compressed,
+            _phantom: PhantomData::default()
+        };
+
+        if let Some(stored) = stored_digest {
+            let mut prefix = Vec::new();
+            bincode::encode_into_std_write(EncodePrefix(&result), &mut prefix, STD_BINCODE_CONFIG)
+                .map_err(encode_io_error)?;
+            if body_digest(&prefix, &result.core) != stored {
+                return Err(corrupt("checksum mismatch"));
+            }
+        }
+
+        Ok(result)
+    }
+
+    /**
+     * Memory-map a map from a file without copying its core blocks.
+     *
+     * Unlike [`read_from_file`](Self::read_from_file), which slurps the whole
+     * file into a [`Vec<u8>`] and then copies every core block into owned
+     * memory, this maps the file into the address space and
+     * [`borrow_decode`](bincode::BorrowDecode::borrow_decode)s directly over the
+     * mapped bytes.  The returned [`MmapCompressedMap`] owns the mapping, and
+     * its cores borrow from it, so no block data is copied.  For a `cnm2`
+     * file, decoding verifies the checksum, which reads every core block once
+     * at load; a `cnm1` file has no checksum, and its block data is paged in
+     * by the OS only as lookups touch it.
+     *
+     * Returns an error under the same conditions as
+     * [`read_from_file`](Self::read_from_file), including leftover bytes at the
+     * end of the file.
+     */
+    pub fn mmap_from_file<P: AsRef<Path>>(path: P) -> Result<MmapCompressedMap<K,V>, Error>
+    where V: BorrowDecode<'static> + Decode + Encode {
+        let file = File::open(path)?;
+        /* Safety: we open the file read-only and never expose the mapping
+         * mutably, so the usual mmap aliasing caveats don't apply here.
+         */
+        let mmap = unsafe { Mmap::map(&file)? };
+        let (map,sz) : (CompressedMap<K,V>,usize)
+            = bincode::borrow_decode_from_slice(&mmap, STD_BINCODE_CONFIG)
+            .map_err(|e| Error::new(ErrorKind::Other, e.to_string()))?;
+        if sz < mmap.len() {
+            return Err(Error::new(ErrorKind::Other, "bytes left over on mmap_from_file".to_string()));
+        }
+
+        /* Safety: the cores borrow from `mmap`, which we move into the same
+         * struct as the map below and never drop or remap for the struct's
+         * lifetime.  Extending the borrow to `'static` lets the two live
+         * together; `MmapCompressedMap` never hands out a reference that
+         * outlives the mapping.
+         */
+        let map : CompressedMap<'static,K,V> = unsafe { core::mem::transmute(map) };
+        Ok(MmapCompressedMap { map, _mmap: mmap })
+    }
+
+    /**
+     * Build a read-only view over an already-serialized buffer.
+     *
+     * Unlike [`read_from_file`](Self::read_from_file), which slurps and then
+     * copies every core block into owned memory, this validates the header and
+     * returns a [`MapView`] whose cores borrow directly from `bytes`.  The
+     * header, response map and salt are still decoded and allocated up front;
+     * what is saved is the entropy-coded core: no block data is copied and the
+     * big bit arrays are never materialized.
+     * The view borrows `bytes` for its lifetime, so a process can put one
+     * read-only [`mmap`](memmap2::Mmap) behind it and share it across threads.
+     * For a `cnm2` buffer, construction verifies the checksum and so reads
+     * every core block once; only a `cnm1` buffer is touched solely by the
+     * bits each `view[k]` lookup reads.
+     *
+     * Returns an error if the header is invalid, the map is corrupt, or there
+     * are trailing bytes after the encoded map.
+     */
+    pub fn from_bytes<'b>(bytes: &'b [u8]) -> Result<MapView<'b,K,V>, Error>
+    where V: BorrowDecode<'b> + Decode + Encode {
+        let (map,sz) : (CompressedMap<'b,K,V>,usize)
+            = bincode::borrow_decode_from_slice(bytes, STD_BINCODE_CONFIG)
+            .map_err(|e| Error::new(ErrorKind::Other, e.to_string()))?;
+        if sz < bytes.len() {
+            return Err(Error::new(ErrorKind::Other, "bytes left over on from_bytes".to_string()));
+        }
+        Ok(MapView { map })
+    }
+}
+
+/**
+ * A read-only [`CompressedMap`] borrowing a serialized buffer.
+ *
+ * Produced by [`CompressedMap::from_bytes`].  The cores point straight into the
+ * backing slice, so lookups touch only the pages they need (though checksum
+ * verification of a `cnm2` buffer has read every block once).  Deref forwards to
+ * the inner map, so `query`, [`Index`](core::ops::Index) and friends work as
+ * usual.
+ */
+pub struct MapView<'a,K,V> {
+    map: CompressedMap<'a,K,V>
+}
+
+impl <'a,K,V> Deref for MapView<'a,K,V> {
+    type Target = CompressedMap<'a,K,V>;
+    fn deref(&self) -> &Self::Target { &self.map }
+}
+
+impl <'a,K:Hash,V,Idx> Index<Idx> for MapView<'a,K,V>
+    where CompressedMap<'a,K,V>: Index<Idx,Output=V> {
+    type Output = V;
+    fn index(&self, index: Idx) -> &V { &self.map[index] }
+}
+
+/**
+ * A [`CompressedMap`] backed by a memory-mapped file.
+ *
+ * Produced by [`CompressedMap::mmap_from_file`].  The map's cores borrow
+ * directly from the mapping, so block data is not copied; for a `cnm2` file
+ * the load-time checksum check has read every block once.
+ * Deref forwards to the inner map, so `query`, [`Index`](core::ops::Index) and
+ * friends work as usual.
+ */
+pub struct MmapCompressedMap<K,V> {
+    /* Field order matters: `map` is dropped before `_mmap`, so the borrowed
+     * cores never outlive the mapping they point into.
+     */
+    map: CompressedMap<'static,K,V>,
+    _mmap: Mmap
+}
+
+impl <K,V> Deref for MmapCompressedMap<K,V> {
+    type Target = CompressedMap<'static,K,V>;
+    fn deref(&self) -> &Self::Target { &self.map }
 }
 
+impl <K:Hash,V,Idx> Index<Idx> for MmapCompressedMap<K,V>
+    where CompressedMap<'static,K,V>: Index<Idx,Output=V> {
+    type Output = V;
+    fn index(&self, index: Idx) -> &V { &self.map[index] }
+}
+
+/** Original container magic: no integrity checksum. */
 const MAGIC: &[u8;4] = b"cnm1";
 
-impl <'a,K,V> Encode for CompressedMap<'a,K,V> where V: Encode {
+/** Checksummed container magic: an 8-byte xxHash-64 digest follows the magic. */
+const MAGIC2: &[u8;4] = b"cnm2";
+
+/** Flag bit in the `cnm2` prefix: response-value region is LZ4-compressed. */
+const FLAG_LZ4: u8 = 1;
+
+/**
+ * Compress the serialized response-value region with LZ4, returning a
+ * length-prefixed frame.  Only reachable when the `lz4` feature is enabled.
+ */
+#[cfg(feature = "lz4")]
+fn compress_values(raw: &[u8]) -> Vec<u8> {
+    lz4_flex::compress_prepend_size(raw)
+}
+
+/** Decompress an LZ4 frame produced by [`compress_values`]. */
+#[cfg(feature = "lz4")]
+fn decompress_values(frame: &[u8]) -> Result<Vec<u8>, DecodeError> {
+    lz4_flex::decompress_size_prepended(frame)
+        .map_err(|e| DecodeError::OtherString(format!("lz4 decompress: {e}")))
+}
+
+/**
+ * Hash the body of a checksummed container: the prefix bytes (everything after
+ * the magic and digest) followed by every core's contiguous block slice.
+ */
+fn body_digest(prefix: &[u8], core: &[MapCore]) -> u64 {
+    let mut hasher = Xxh64::new(0);
+    hasher.update(prefix);
+    for c in core { hasher.update(c.blocks.as_ref()); }
+    hasher.digest()
+}
+
+/** Decode a single [`Decode`] value from a reader, mapping errors to I/O errors. */
+fn dread<T: Decode, R: Read>(r: &mut R) -> Result<T, Error> {
+    bincode::decode_from_std_read(r, STD_BINCODE_CONFIG).map_err(decode_io_error)
+}
+
+/** Map a [`DecodeError`] back to an [`io::Error`](std::io::Error). */
+fn decode_io_error(e: DecodeError) -> Error {
+    match e {
+        DecodeError::Io{ inner, .. } => inner,
+        _ => Error::new(ErrorKind::Other, e.to_string()),
+    }
+}
+
+/**
+ * A [`Write`] that offloads the actual writes to a background thread.
+ *
+ * Buffers handed to [`write`](Write::write) are shipped over a bounded channel
+ * to a dedicated thread that performs the real syscalls, so a producer can keep
+ * serializing while the previous section is still draining to disk or a socket.
+ * The channel depth bounds how far ahead the producer may run.  Errors from the
+ * writer thread surface on a later [`write`](Write::write) or on
+ * [`finish`](Self::finish).
+ */
+pub struct BackgroundWriter {
+    tx: Option<SyncSender<Vec<u8>>>,
+    handle: Option<JoinHandle<Result<(), Error>>>
+}
+
+impl BackgroundWriter {
+    /** Wrap `inner`, queuing up to `depth` pending buffers (minimum 1). */
+    pub fn new<W: Write + Send + 'static>(mut inner: W, depth: usize) -> Self {
+        let (tx,rx) = sync_channel::<Vec<u8>>(max(1,depth));
+        let handle = std::thread::spawn(move || {
+            for buf in rx.iter() {
+                inner.write_all(&buf)?;
+            }
+            inner.flush()
+        });
+        BackgroundWriter { tx: Some(tx), handle: Some(handle) }
+    }
+
+    /** Drain the queue and return any error from the writer thread. */
+    pub fn finish(mut self) -> Result<(), Error> { self.join() }
+
+    fn join(&mut self) -> Result<(), Error> {
+        drop(self.tx.take());
+        match self.handle.take() {
+            Some(h) => h.join().unwrap_or_else(|_| Err(Error::new(ErrorKind::Other, "writer thread panicked"))),
+            None => Ok(())
+        }
+    }
+}
+
+impl Write for BackgroundWriter {
+    fn write(&mut self, buf: &[u8]) -> Result<usize, Error> {
+        match &self.tx {
+            Some(tx) => tx.send(buf.to_vec())
+                .map(|_| buf.len())
+                .map_err(|_| Error::new(ErrorKind::BrokenPipe, "background writer stopped")),
+            None => Err(Error::new(ErrorKind::BrokenPipe, "background writer finished"))
+        }
+    }
+    fn flush(&mut self) -> Result<(), Error> { Ok(()) }
+}
+
+impl Drop for BackgroundWriter {
+    fn drop(&mut self) { let _ = self.join(); }
+}
+
+/** Map an [`EncodeError`] back to an [`io::Error`](std::io::Error). */
+fn encode_io_error(e: EncodeError) -> Error {
+    match e {
+        EncodeError::Io{ error:e, index:_s } => e,
+        EncodeError::Other(s) => Error::new(ErrorKind::Other, s),
+        _ => Error::new(ErrorKind::Other, e.to_string()),
+    }
+}
+
+/**
+ * Write every buffer in `bufs` in order, gathering them into one
+ * [`write_vectored`](Write::write_vectored) call where possible.
+ *
+ * Writers that don't actually gather (they report writing only the first
+ * buffer) are handled transparently: the loop simply advances buffer by
+ * buffer, degrading to sequential [`write_all`](Write::write_all) behaviour.
+ */
+fn write_all_vectored<W: Write>(w: &mut W, bufs: &[&[u8]]) -> Result<(), Error> {
+    let mut idx = 0; /* current buffer */
+    let mut off = 0; /* offset into current buffer */
+    while idx < bufs.len() {
+        if bufs[idx].len() == off { idx += 1; off = 0; continue; }
+        let mut slices : Vec<IoSlice> = Vec::with_capacity(bufs.len()-idx);
+        slices.push(IoSlice::new(&bufs[idx][off..]));
+        for b in &bufs[idx+1..] { slices.push(IoSlice::new(b)); }
+
+        let mut rem = w.write_vectored(&slices)?;
+        if rem == 0 {
+            return Err(Error::new(ErrorKind::WriteZero, "failed to write whole buffer"));
+        }
+
+        /* Advance (idx,off) by `rem` bytes across the buffer list. */
+        let first_avail = bufs[idx].len() - off;
+        if rem < first_avail { off += rem; continue; }
+        rem -= first_avail; idx += 1; off = 0;
+        while idx < bufs.len() && rem >= bufs[idx].len() {
+            rem -= bufs[idx].len(); idx += 1;
+        }
+        off = rem;
+    }
+    Ok(())
+}
+
+/**
+ * Newtype that encodes the body prefix of a map: the header/response/nblocks
+ * section that precedes the raw block payloads, but *not* the magic or digest.
+ */
+struct EncodePrefix<'x,'a,K,V>(&'x CompressedMap<'a,K,V>);
+
+impl <'x,'a,K,V> Encode for EncodePrefix<'x,'a,K,V> where V: Encode {
     fn encode<E: Encoder>(&self, encoder: &mut E) -> Result<(), EncodeError> {
-        Encode::encode(MAGIC, encoder)?;
+        let me = self.0;
 
-        assert!(self.response_map.len() >= 1);
-        let mut log_responses = Vec::with_capacity(self.response_map.len()-1);
-        for i in 0..self.response_map.len()-1 {
-            let delta = self.response_map[i+1].0 - self.response_map[i].0;
+        assert!(me.response_map.len() >= 1);
+        let mut log_responses = Vec::with_capacity(me.response_map.len()-1);
+        for i in 0..me.response_map.len()-1 {
+            let delta = me.response_map[i+1].0 - me.response_map[i].0;
             log_responses.push(delta.leading_zeros() as u8+1);
         }
-        let hash_key = if self.core.len() == 0 {
+        let hash_key = if me.core.len() == 0 {
             [0u8;16]
         } else {
-            self.core[0].hash_key
+            me.core[0].hash_key
         };
 
+        /* Flags byte records format options for the value region.  The common
+         * case writes each value inline (zero-copy on decode); when LZ4 is
+         * requested and actually shrinks, the whole region is replaced by a
+         * single length-prefixed compressed frame and `FLAG_LZ4` is set.
+         */
+        #[allow(unused_mut)]
+        let mut flags = 0u8;
+        #[allow(unused_mut)]
+        let mut compressed : Option<Vec<u8>> = None;
+        #[cfg(feature = "lz4")]
+        if me.compress_values {
+            let mut vals = Vec::new();
+            for (_l,v) in &me.response_map {
+                bincode::encode_into_std_write(v, &mut vals, STD_BINCODE_CONFIG)?;
+            }
+            let frame = compress_values(&vals);
+            /* Only keep the compressed form if it actually shrinks. */
+            if frame.len() < vals.len() {
+                flags |= FLAG_LZ4;
+                compressed = Some(frame);
+            }
+        }
+
+        Encode::encode(&flags, encoder)?;
         Encode::encode(&log_responses, encoder)?;
-        for (_l,v) in &self.response_map {
-            Encode::encode(v, encoder)?;
+        if let Some(frame) = compressed {
+            Encode::encode(&frame, encoder)?;
+        } else {
+            for (_l,v) in &me.response_map {
+                Encode::encode(v, encoder)?;
+            }
         }
 
         Encode::encode(&hash_key, encoder)?;
-        Encode::encode(&self.plan, encoder)?;
-        encoder.writer().write(&self.salt)?;
-        for core in &self.core {
+        Encode::encode(&me.plan, encoder)?;
+        encoder.writer().write(&me.salt)?;
+        for core in &me.core {
             Encode::encode(&core.nblocks,encoder)?;
         }
+        Ok(())
+    }
+}
+
+impl <'a,K,V> Encode for CompressedMap<'a,K,V> where V: Encode {
+    fn encode<E: Encoder>(&self, encoder: &mut E) -> Result<(), EncodeError> {
+        /* Buffer the body prefix so we can checksum it before emitting. */
+        let mut prefix = Vec::new();
+        bincode::encode_into_std_write(EncodePrefix(self), &mut prefix, STD_BINCODE_CONFIG)?;
+        let digest = body_digest(&prefix, &self.core);
+
+        Encode::encode(MAGIC2, encoder)?;
+        Encode::encode(&digest.to_le_bytes(), encoder)?;
+        encoder.writer().write(&prefix)?;
         for core in &self.core {
             encoder.writer().write(&core.blocks.as_ref())?;
         }
@@ -508,22 +1119,59 @@ impl <'a,K,V> Encode for CompressedMap<'a,K,V> where V: Encode {
     }
 }
 
-impl <'a,'de:'a,K,V> BorrowDecode<'de> for CompressedMap<'a,K,V> where V: BorrowDecode<'de> {
+impl <'a,'de:'a,K,V> BorrowDecode<'de> for CompressedMap<'a,K,V> where V: BorrowDecode<'de> + Decode + Encode {
     fn borrow_decode<D: BorrowDecoder<'de>>(decoder: &mut D) -> Result<Self, DecodeError> {
         /* Decode the response map */
         fn err<Nope>(descr: &'static str) -> Result<Nope, DecodeError> {
             Err(DecodeError::OtherString(descr.to_string()))
         }
         let magic : [u8;4] = Decode::decode(decoder)?;
-        if &magic != MAGIC {
+        let checksummed = if &magic == MAGIC2 {
+            true
+        } else if &magic == MAGIC {
+            false
+        } else {
             return err("magic value mismatch");
-        }
+        };
+
+        /* `cnm2` carries an 8-byte xxHash-64 digest of the body right after the
+         * magic; `cnm1` files have no digest and are accepted for compatibility.
+         */
+        let stored_digest = if checksummed {
+            let raw : [u8;8] = Decode::decode(decoder)?;
+            Some(u64::from_le_bytes(raw))
+        } else {
+            None
+        };
+
+        /* Flags byte (cnm2 only); cnm1 files predate it and are uncompressed. */
+        let flags : u8 = if checksummed { Decode::decode(decoder)? } else { 0 };
+        let compressed = (flags & FLAG_LZ4) != 0;
 
         /* First: log_responses and responses */
         let log_responses : Vec<u8> = Decode::decode(decoder)?;
-        let mut responses : Vec<V> = Vec::with_capacity(log_responses.len()+1);
-        for _ in 0..log_responses.len()+1 {
-            responses.push(BorrowDecode::borrow_decode(decoder)?);
+        let nresp = log_responses.len()+1;
+        let mut responses : Vec<V> = Vec::with_capacity(nresp);
+        if compressed {
+            #[cfg(feature = "lz4")]
+            {
+                /* The whole value region is one length-prefixed LZ4 frame. */
+                let frame : Vec<u8> = Decode::decode(decoder)?;
+                let raw = decompress_values(&frame)?;
+                let mut off = 0;
+                for _ in 0..nresp {
+                    let (v,used) : (V,usize) =
+                        bincode::decode_from_slice(&raw[off..], STD_BINCODE_CONFIG)?;
+                    responses.push(v);
+                    off += used;
+                }
+            }
+            #[cfg(not(feature = "lz4"))]
+            return err("value region is LZ4-compressed but the lz4 feature is not enabled");
+        } else {
+            for _ in 0..nresp {
+                responses.push(BorrowDecode::borrow_decode(decoder)?);
+            }
         }
         let mut response_map = Vec::with_capacity(responses.len());
         let mut total : Locator = 0;
@@ -592,13 +1240,30 @@ impl <'a,'de:'a,K,V> BorrowDecode<'de> for CompressedMap<'a,K,V> where V: Borrow
             }
         }
 
-        Ok(CompressedMap{
+        let result = CompressedMap{
             plan: plan,
             response_map: response_map,
             salt: salt,
             core: core,
+            compress_values: compressed,
             _phantom: PhantomData::default()
-        })
+        };
+
+        /* Verify the checksum by reconstructing the body we would have emitted.
+         * The prefix re-encodes deterministically from the decoded fields, and
+         * the block payloads are still borrowed from the input, so the digest
+         * matches exactly what `encode`/`write_to` wrote.
+         */
+        if let Some(stored) = stored_digest {
+            let mut prefix = Vec::new();
+            bincode::encode_into_std_write(EncodePrefix(&result), &mut prefix, STD_BINCODE_CONFIG)
+                .map_err(|e| DecodeError::OtherString(e.to_string()))?;
+            if body_digest(&prefix, &result.core) != stored {
+                return err("checksum mismatch");
+            }
+        }
+
+        Ok(result)
     }
 }
 
@@ -612,28 +1277,632 @@ impl <'a,K:Hash,V> Index<K> for CompressedMap<'a,K,V> where {
     fn index(&self, index: K) -> &V { self.query(&index) }
 }
 
-/** Utility: vector with bitset selecting which of its elements are iterated over. */
-struct FilteredVec<'a,T> {
-    vec: Vec<(&'a T,Locator)>,
-    filter: BitSet
+/** Layout of a single build phase, as reported by [`CompressedMap::describe`]. */
+#[derive(Clone,Copy,PartialEq,Eq,Debug)]
+pub struct PhaseStats {
+    /** Lowest locator bit this phase resolves. */
+    pub shift: u32,
+    /** Number of locator bits resolved in this phase. */
+    pub nbits: u32,
+    /** Number of core blocks the phase's solver produced. */
+    pub nblocks: usize,
+    /** Serialized size of the phase's core blocks, in bytes. */
+    pub bytes: usize
 }
 
-struct FilteredVecIterator<'a,T> {
-    vec: &'a Vec<(&'a T,Locator)>,
-    bsi: BitSetIterator<'a>
+/** One response interval, as reported by [`CompressedMap::describe`]. */
+#[derive(Clone,Copy,PartialEq,Debug)]
+pub struct IntervalStats {
+    /** Index of the value in the response map. */
+    pub value_index: usize,
+    /** Inclusive lower bound of the locator interval. */
+    pub lo: Locator,
+    /** Width of the interval; `1<<32` for a single-value map. */
+    pub width: u64,
+    /** Fraction of the locator space covered, i.e. `width / 2^32`. */
+    pub probability: f64
 }
 
-impl <'a,T> Iterator for FilteredVecIterator<'a,T> {
-    type Item = (&'a T, &'a Locator);
-    fn size_hint(&self) -> (usize,Option<usize>) { self.bsi.size_hint() }
-    fn next(&mut self) -> Option<(&'a T, &'a Locator)> {
-        let i = self.bsi.next()?;
-        let (k,v) = &self.vec[i];
-        Some((k,&v))
+/**
+ * Structural report for a built [`CompressedMap`], returned by
+ * [`describe`](CompressedMap::describe).
+ *
+ * It is plain data, so callers can inspect individual fields, and it also
+ * implements [`Display`] to print a readable table.  The `bits_per_key`
+ * figure can be compared against `entropy` to sanity-check the documented
+ * ~11% overhead bound; note it is derived from a capacity upper bound on the
+ * key count, so it reads as a lower bound on the true bits-per-key.  `entropy`
+ * is computed from the rounded interval widths, so the comparison does not
+ * include the overhead of that rounding.
+ */
+#[derive(Clone,PartialEq,Debug)]
+pub struct MapStats {
+    /** Per-phase layout, in resolution order. */
+    pub phases: Vec<PhaseStats>,
+    /** Response intervals and their probabilities. */
+    pub intervals: Vec<IntervalStats>,
+    /** Shannon entropy of the interval probabilities (the value distribution
+     * as rounded by the plan, not the exact input counts), in bits per key. */
+    pub entropy: f64,
+    /** Widest phase's block-slot capacity: an upper bound on the key count. */
+    pub estimated_keys: usize,
+    /** Total size of the core blocks, in bytes. */
+    pub core_bytes: usize,
+    /** Total serialized size of the whole map, in bytes. */
+    pub serialized_bytes: usize,
+    /** Core bits per key, against the capacity upper bound; a lower bound on
+     * the true figure.  Compare against `entropy`. */
+    pub bits_per_key: f64
+}
+
+impl Display for MapStats {
+    fn fmt(&self, f: &mut Formatter) -> fmt::Result {
+        writeln!(f, "phases ({}):", self.phases.len())?;
+        for (i,p) in self.phases.iter().enumerate() {
+            writeln!(f, "  [{i}] shift {:2} nbits {:2} nblocks {:6} {:8} bytes",
+                p.shift, p.nbits, p.nblocks, p.bytes)?;
+        }
+        writeln!(f, "responses ({}):", self.intervals.len())?;
+        for iv in &self.intervals {
+            writeln!(f, "  value {:3} lo {:#010x} width {:11} p {:.6}",
+                iv.value_index, iv.lo, iv.width, iv.probability)?;
+        }
+        writeln!(f, "core bytes      : {}", self.core_bytes)?;
+        writeln!(f, "serialized bytes: {}", self.serialized_bytes)?;
+        writeln!(f, "keys (capacity) : {} (upper bound)", self.estimated_keys)?;
+        writeln!(f, "entropy         : {:.4} bits/key", self.entropy)?;
+        let overhead = if self.entropy > 0.0 {
+            (self.bits_per_key / self.entropy - 1.0) * 100.0
+        } else {
+            0.0
+        };
+        writeln!(f, "bits per key    : {:.4} ({:+.1}% vs entropy; lower bound)", self.bits_per_key, overhead)
     }
 }
 
-impl <'a,T> ExactSizeIterator for FilteredVecIterator<'a,T> {}
+/** Container magic for a serialized [`CascadeSet`]. */
+const CASCADE_MAGIC: &[u8;4] = b"cset";
+
+/**
+ * An approximate-membership set built on the static-function machinery.
+ *
+ * A [`CascadeSet`] answers membership queries over a known universe split into
+ * positives `P` and negatives `N`, with **zero false negatives** and a tunable
+ * false-positive rate on keys outside the universe.  This is the shape that
+ * revocation systems such as CRLite need: `P` is short-lived (e.g. revoked
+ * certificates) and `N` is large (everything else), and the set must never
+ * claim a positive is absent.
+ *
+ * The construction is a fingerprint cascade.  Layer 0 is a static function
+ * (built via the same path as [`CompressedMap`]) fingerprinting every key in
+ * `P`; running `N` through it yields the colliding subset `FP0 ⊆ N`.  Layer 1
+ * fingerprints `FP0`; running `P` through it yields `FP1 ⊆ P`; and so on,
+ * alternating which collection is filtered, until the carried-over collision
+ * set is empty.  A query walks the layers in order and the first layer that
+ * reports "absent" decides the verdict: the key is a member iff that layer has
+ * an odd index (or it survives every layer).
+ *
+ * Each layer fingerprints its keys to the value `0` with a fixed bit width, so
+ * a key in the layer's build set always tests present while an unrelated key
+ * tests present with probability `2^-width`.  Layer 0 uses
+ * `ceil(log2(1/fp_rate))` bits and inner layers use a single bit, which keeps
+ * the total near the information-theoretic optimum of `~1.44·|P|·log2(1/fp)`
+ * bits.  A cascade that would end on an odd layer gets one more layer with a
+ * 32-bit fingerprint over its positive-derived keys, which adds 32 bits per
+ * such key on top of that figure.
+ */
+#[derive(Eq,PartialEq,Debug)]
+pub struct CascadeSet<'a,K> {
+    layers: Vec<MapCore<'a>>,
+    _phantom: PhantomData<K>
+}
+
+impl <'a,K> Clone for CascadeSet<'a,K> {
+    fn clone(&self) -> Self {
+        CascadeSet { layers: self.layers.clone(), _phantom: PhantomData::default() }
+    }
+}
+
+impl <'a,K:Hash> CascadeSet<'a,K> {
+    /**
+     * Build a cascade set over disjoint collections `positives` and `negatives`.
+     *
+     * Returns `None` if a layer's static function cannot be solved, or if
+     * `fp_rate` is not in `(0,1)`.  `positives` and `negatives` must be
+     * disjoint; a key present in both would force the cascade to grow without
+     * terminating.  The deterministic `key_gen` seed in `options` makes the
+     * whole cascade reproducible.
+     */
+    pub fn build(positives: &[K], negatives: &[K], fp_rate: f64, options: &mut BuildOptions)
+        -> Option<CascadeSet<'static,K>>
+    {
+        if !(fp_rate > 0.0 && fp_rate < 1.0) { return None; }
+        let w0 = max(1, (-fp_rate.log2()).ceil() as u32);
+
+        let mut layers : Vec<MapCore<'static>> = Vec::new();
+        let mut build_set : Vec<&K> = positives.iter().collect();
+        let mut test_set  : Vec<&K> = negatives.iter().collect();
+        let mut layer = 0;
+        while !build_set.is_empty() {
+            let width = if layer == 0 { w0 } else { 1 };
+            let core = Self::build_layer(&build_set, width, layer, options)?;
+
+            /* Carry forward the keys in the other collection that collide. */
+            let mut fp : Vec<&K> = Vec::new();
+            for &k in &test_set {
+                if core.query_hash(k) == 0 { fp.push(k); }
+            }
+            layers.push(core.take_ownership());
+
+            if fp.is_empty() {
+                /* The opposite set is fully resolved.  On an odd layer the keys
+                 * we just fingerprinted are negative-derived and test present in
+                 * every layer built so far, so `contains` would wrongly report
+                 * them as members.  No positive reaches this far (their chain is
+                 * what just emptied), so append one positive-derived layer over
+                 * the current `test_set` to end the cascade on an even layer.
+                 * It uses a full-width fingerprint so those residual negatives
+                 * test absent there with overwhelming probability and are
+                 * rejected, rather than the 1-bit inner width that would let
+                 * half of them slip through. */
+                if layer % 2 == 1 {
+                    let core = Self::build_layer(&test_set, Locator::BITS, layer+1, options)?;
+                    layers.push(core.take_ownership());
+                }
+                break;
+            }
+            test_set = build_set;
+            build_set = fp;
+            layer += 1;
+        }
+
+        Some(CascadeSet { layers, _phantom: PhantomData::default() })
+    }
+
+    /** Build one fingerprint layer: a static function mapping every key in
+     * `set` to the `width`-bit value `0`. */
+    fn build_layer(set: &[&K], width: u32, layer: usize, options: &BuildOptions)
+        -> Option<MapCore<'static>>
+    {
+        let n = set.len();
+        let mut filtered = FilteredVec {
+            vec: set.iter().map(|&k| (k, 0 as Locator)).collect(),
+            filter: BitSet::with_capacity(n)
+        };
+        filtered.filter.union_with_range(0..n);
+
+        let layer_key = match options.key_gen {
+            Some(k) => Some(choose_key(Some(k), layer)),
+            None    => None
+        };
+        let mut layer_options = BuildOptions {
+            max_tries: options.max_tries,
+            try_num: 0,
+            key_gen: layer_key,
+            bits_per_value: Some(width as u8),
+            shift: 0,
+            max_threads: options.max_threads
+        };
+        let map = CompressedRandomMap::<K,Locator>::build::<FilteredVec<K>>(&filtered, &mut layer_options)?;
+        Some(map.core.take_ownership())
+    }
+
+    /**
+     * Test whether `k` is a member.
+     *
+     * Never reports a true positive as absent.  A key outside the original
+     * universe is reported present with probability bounded by the configured
+     * false-positive rate.
+     */
+    pub fn contains(&self, k:&K) -> bool {
+        for (i,layer) in self.layers.iter().enumerate() {
+            if layer.query_hash(k) != 0 {
+                /* First layer to report "absent" decides the verdict. */
+                return i & 1 == 1;
+            }
+        }
+        /* Survived every layer. */
+        true
+    }
+
+    /** Number of cascade layers. */
+    pub fn len(&self) -> usize { self.layers.len() }
+
+    /** True if the cascade has no layers. */
+    pub fn is_empty(&self) -> bool { self.layers.is_empty() }
+
+    /** Take ownership of the layer blocks, copying if they were borrowed. */
+    pub fn take_ownership<'b>(self) -> CascadeSet<'b,K> {
+        CascadeSet {
+            layers: self.layers.into_iter().map(|c| c.take_ownership()).collect(),
+            _phantom: PhantomData::default()
+        }
+    }
+}
+
+impl <'a,K> Encode for CascadeSet<'a,K> {
+    fn encode<E: Encoder>(&self, encoder: &mut E) -> Result<(), EncodeError> {
+        Encode::encode(CASCADE_MAGIC, encoder)?;
+        Encode::encode(&self.layers.len(), encoder)?;
+        for core in &self.layers {
+            Encode::encode(&core.hash_key, encoder)?;
+            Encode::encode(&core.bits_per_value, encoder)?;
+            Encode::encode(&core.nblocks, encoder)?;
+        }
+        for core in &self.layers {
+            encoder.writer().write(core.blocks.as_ref())?;
+        }
+        Ok(())
+    }
+}
+
+impl <'a,'de:'a,K> BorrowDecode<'de> for CascadeSet<'a,K> {
+    fn borrow_decode<D: BorrowDecoder<'de>>(decoder: &mut D) -> Result<Self, DecodeError> {
+        fn err<Nope>(descr: &'static str) -> Result<Nope, DecodeError> {
+            Err(DecodeError::OtherString(descr.to_string()))
+        }
+        let magic : [u8;4] = Decode::decode(decoder)?;
+        if &magic != CASCADE_MAGIC { return err("cascade magic mismatch"); }
+
+        let nlayers : usize = Decode::decode(decoder)?;
+        let mut heads = Vec::with_capacity(nlayers);
+        for _ in 0..nlayers {
+            let hash_key : [u8;16] = Decode::decode(decoder)?;
+            let bits_per_value : u8 = Decode::decode(decoder)?;
+            let nblocks : usize = Decode::decode(decoder)?;
+            if bits_per_value == 0 || bits_per_value as u32 > Locator::BITS {
+                return err("invalid bits_per_value");
+            }
+            if nblocks < 2 { return err("must have at least 2 nblocks"); }
+            heads.push((hash_key,bits_per_value,nblocks));
+        }
+
+        let mut layers = Vec::with_capacity(nlayers);
+        for (hash_key,bits_per_value,nblocks) in heads {
+            let len = nblocks.checked_mul(BLOCKSIZE)
+                .and_then(|x| x.checked_mul(bits_per_value as usize))
+                .ok_or(DecodeError::OtherString("overflow on multiply".to_string()))?;
+            let borrowed = decoder.borrow_reader().take_bytes(len)?;
+            layers.push(MapCore {
+                hash_key,
+                bits_per_value,
+                nblocks,
+                blocks: Cow::Borrowed(borrowed)
+            });
+        }
+
+        Ok(CascadeSet { layers, _phantom: PhantomData::default() })
+    }
+}
+
+/** Container magic for a serialized [`ShardedMap`]. */
+const SHARD_MAGIC: &[u8;4] = b"cshd";
+
+/** Assign a key to one of `nshards` shards by a stable hash. */
+fn shard_of<K:Hash>(k:&K, nshards:usize) -> usize {
+    use core::hash::Hasher;
+    use std::collections::hash_map::DefaultHasher;
+    let mut h = DefaultHasher::new();
+    k.hash(&mut h);
+    (h.finish() % nshards as u64) as usize
+}
+
+/**
+ * A [`CompressedMap`] partitioned into independent shards for parallel builds.
+ *
+ * Building a single map is single-threaded and becomes the bottleneck for
+ * inputs with tens of millions of entries.  A [`ShardedMap`] hashes each key
+ * into one of `shards` partitions, builds each partition's [`CompressedMap`]
+ * on its own thread, and stores them in a top-level array indexed by the shard
+ * hash.  A lookup recomputes the shard from the key and dispatches into the
+ * matching sub-map, so query cost is unchanged.
+ *
+ * The shard assignment and each shard's build key are derived deterministically
+ * from the key and the `key_gen` seed, so a given seed and shard count always
+ * build the same map.  Every input key answers with its own value whatever the
+ * shard count; the arbitrary answers for keys outside the input depend on it.
+ */
+#[derive(Eq,PartialEq,Debug)]
+pub struct ShardedMap<'a,K,V> {
+    shards: Vec<Option<CompressedMap<'a,K,V>>>,
+    _phantom: PhantomData<K>
+}
+
+impl <'a,K,V> Clone for ShardedMap<'a,K,V> where V:Clone {
+    fn clone(&self) -> Self {
+        ShardedMap { shards: self.shards.clone(), _phantom: PhantomData::default() }
+    }
+}
+
+impl <'a,K:Hash,V> ShardedMap<'a,K,V> {
+    /**
+     * Build a sharded map, partitioning the input across up to `threads`
+     * worker threads.
+     *
+     * `shards` is clamped to at least 1; with a single shard this degrades to a
+     * plain [`CompressedMap::build`].  Returns `None` if the input is empty or
+     * any shard fails to build.
+     */
+    pub fn build<'b, Collection>(map: &'b Collection, shards: usize, threads: usize,
+                                 options: &mut BuildOptions) -> Option<ShardedMap<'static,K,V>>
+    where &'b Collection: IntoIterator<Item=(&'b K, &'b V)>,
+          K: 'b + Send + Sync,
+          V: 'b + Hash + Ord + Clone + Send + Sync,
+          <&'b Collection as IntoIterator>::IntoIter : ExactSizeIterator
+    {
+        let nshards = max(1, shards);
+
+        /* Partition the input by shard hash into reference buckets. */
+        let mut buckets : Vec<RefPairs<K,V>> =
+            (0..nshards).map(|_| RefPairs { pairs: Vec::new() }).collect();
+        for (k,v) in map {
+            buckets[shard_of(k,nshards)].pairs.push((k,v));
+        }
+
+        let base = options.key_gen;
+        let max_tries = options.max_tries;
+        let max_threads = options.max_threads;
+
+        /* Deal the shards round-robin to the worker threads. */
+        let nthreads = max(1, min(threads, nshards));
+        let mut groups : Vec<Vec<(usize,RefPairs<K,V>)>> =
+            (0..nthreads).map(|_| Vec::new()).collect();
+        for (i,bucket) in buckets.into_iter().enumerate() {
+            groups[i % nthreads].push((i,bucket));
+        }
+
+        /* `Ok(None)` marks a legitimately empty shard; `Err(())` marks a
+         * populated shard whose build failed.  The two must not be conflated:
+         * an empty shard is fine, a failed one means the whole map is invalid.
+         */
+        let built : Vec<Vec<(usize,Result<Option<CompressedMap<'static,K,V>>,()>)>> =
+            std::thread::scope(|scope| {
+                let handles : Vec<_> = groups.into_iter().map(|group| {
+                    scope.spawn(move || {
+                        group.into_iter().map(|(i,bucket)| {
+                            if bucket.pairs.is_empty() { return (i,Ok(None)); }
+                            let mut opts = BuildOptions {
+                                max_tries,
+                                try_num: 0,
+                                key_gen: base.map(|k| choose_key(Some(k), i)),
+                                bits_per_value: None,
+                                shift: 0,
+                                max_threads
+                            };
+                            let built = match CompressedMap::build(&bucket, &mut opts) {
+                                Some(m) => Ok(Some(m.take_ownership())),
+                                None    => Err(()),
+                            };
+                            (i,built)
+                        }).collect::<Vec<_>>()
+                    })
+                }).collect();
+                handles.into_iter().map(|h| h.join().unwrap()).collect()
+            });
+
+        /* Reassemble into shard order, failing if any populated shard failed. */
+        let mut slots : Vec<Option<CompressedMap<'static,K,V>>> =
+            (0..nshards).map(|_| None).collect();
+        let mut any = false;
+        for group in built {
+            for (i,built) in group {
+                match built {
+                    Ok(Some(m)) => { slots[i] = Some(m); any = true; }
+                    Ok(None)    => { /* Empty shard: nothing to place. */ }
+                    Err(())     => return None, /* A populated shard failed to build. */
+                }
+            }
+        }
+        if !any { return None; }
+
+        Some(ShardedMap { shards: slots, _phantom: PhantomData::default() })
+    }
+
+    pub fn query<'b>(&'b self, key:&K) -> &'b V {
+        let n = self.shards.len();
+        if let Some(m) = &self.shards[shard_of(key,n)] {
+            return m.query(key);
+        }
+        /* Key hashed to an empty shard, so it was never inserted: any value is
+         * an acceptable answer.  Fall back to the first populated shard. */
+        for m in self.shards.iter().flatten() {
+            return m.query(key);
+        }
+        unreachable!("ShardedMap must have at least one populated shard")
+    }
+
+    /** Number of shards. */
+    pub fn num_shards(&self) -> usize { self.shards.len() }
+}
+
+/** Reference bucket feeding [`CompressedMap::build`] from a borrowed slice. */
+struct RefPairs<'a,K,V> {
+    pairs: Vec<(&'a K, &'a V)>
+}
+
+struct RefPairsIter<'b,'a,K,V> {
+    inner: core::slice::Iter<'b,(&'a K, &'a V)>
+}
+
+impl <'b,'a:'b,K,V> Iterator for RefPairsIter<'b,'a,K,V> {
+    type Item = (&'b K, &'b V);
+    fn size_hint(&self) -> (usize,Option<usize>) { self.inner.size_hint() }
+    fn next(&mut self) -> Option<(&'b K, &'b V)> {
+        self.inner.next().map(|&(k,v)| (k,v))
+    }
+}
+
+impl <'b,'a:'b,K,V> ExactSizeIterator for RefPairsIter<'b,'a,K,V> {}
+
+impl <'b,'a:'b,K,V> IntoIterator for &'b RefPairs<'a,K,V> {
+    type Item = (&'b K, &'b V);
+    type IntoIter = RefPairsIter<'b,'a,K,V>;
+    fn into_iter(self) -> RefPairsIter<'b,'a,K,V> {
+        RefPairsIter { inner: self.pairs.iter() }
+    }
+}
+
+impl <'a,K,V> Encode for ShardedMap<'a,K,V> where V: Encode {
+    fn encode<E: Encoder>(&self, encoder: &mut E) -> Result<(), EncodeError> {
+        Encode::encode(SHARD_MAGIC, encoder)?;
+        Encode::encode(&self.shards, encoder)
+    }
+}
+
+impl <'a,'de:'a,K,V> BorrowDecode<'de> for ShardedMap<'a,K,V>
+    where V: BorrowDecode<'de> + Decode + Encode {
+    fn borrow_decode<D: BorrowDecoder<'de>>(decoder: &mut D) -> Result<Self, DecodeError> {
+        let magic : [u8;4] = Decode::decode(decoder)?;
+        if &magic != SHARD_MAGIC {
+            return Err(DecodeError::OtherString("sharded magic mismatch".to_string()));
+        }
+        let shards : Vec<Option<CompressedMap<'a,K,V>>> = BorrowDecode::borrow_decode(decoder)?;
+        Ok(ShardedMap { shards, _phantom: PhantomData::default() })
+    }
+}
+
+impl <'a,K:Hash,V> Index<&K> for ShardedMap<'a,K,V> {
+    type Output = V;
+    fn index(&self, index: &K) -> &V { self.query(index) }
+}
+
+impl <'a,K:Hash,V> Index<K> for ShardedMap<'a,K,V> {
+    type Output = V;
+    fn index(&self, index: K) -> &V { self.query(&index) }
+}
+
+/** Container magic for a serialized [`DictMap`]. */
+const DICT_MAGIC: &[u8;4] = b"cdct";
+
+/**
+ * A [`CompressedMap`] over arbitrary values, stored by dictionary index.
+ *
+ * The base [`CompressedMap`] maps keys to `u32` locators; for tables whose
+ * values come from a small domain (a handful of enums, say), a [`DictMap`]
+ * builds a dictionary of the distinct values and stores only the dictionary
+ * index per key in the entropy-optimal core.  So a map with three outcomes
+ * costs about two bits per key plus a tiny value table, rather than serializing
+ * every value.  The plain generic [`CompressedMap`] remains the default.
+ */
+#[derive(Eq,PartialEq,Debug)]
+pub struct DictMap<'a,K,V> {
+    dict: Vec<V>,
+    indices: CompressedMap<'a,K,u32>,
+    _phantom: PhantomData<K>
+}
+
+impl <'a,K,V> Clone for DictMap<'a,K,V> where V:Clone {
+    fn clone(&self) -> Self {
+        DictMap { dict: self.dict.clone(), indices: self.indices.clone(), _phantom: PhantomData::default() }
+    }
+}
+
+impl <'a,K:Hash,V> DictMap<'a,K,V> {
+    /**
+     * Build a dictionary-coded map.
+     *
+     * Returns `None` on the same conditions as [`CompressedMap::build`] (an
+     * empty input, or an unsolvable core).  The dictionary is ordered, so a
+     * given input builds identically across runs.
+     */
+    pub fn build<'b, Collection>(map: &'b Collection, options: &mut BuildOptions)
+        -> Option<DictMap<'static,K,V>>
+    where &'b Collection: IntoIterator<Item=(&'b K, &'b V)>,
+          K: 'b, V: 'b + Hash + Ord + Clone,
+          <&'b Collection as IntoIterator>::IntoIter : ExactSizeIterator
+    {
+        /* Ordered dictionary of distinct values. */
+        let mut distinct : std::collections::BTreeSet<&V> = std::collections::BTreeSet::new();
+        for (_k,v) in map { distinct.insert(v); }
+        let mut dict_index : HashMap<&V,u32> = HashMap::new();
+        let mut dict = Vec::with_capacity(distinct.len());
+        for (i,v) in distinct.into_iter().enumerate() {
+            dict_index.insert(v, i as u32);
+            dict.push(v.clone());
+        }
+
+        /* Store the per-key index in the entropy-optimal core. */
+        let mut vec : Vec<(&K,Locator)> = Vec::new();
+        for (k,v) in map { vec.push((k, dict_index[v])); }
+        let n = vec.len();
+        let mut filter = BitSet::with_capacity(n);
+        filter.union_with_range(0..n);
+        let indices = CompressedMap::<K,u32>::build::<FilteredVec<K>>(
+            &FilteredVec { vec, filter }, options)?.take_ownership();
+
+        Some(DictMap { dict, indices, _phantom: PhantomData::default() })
+    }
+
+    pub fn query<'b>(&'b self, key:&K) -> &'b V {
+        &self.dict[*self.indices.query(key) as usize]
+    }
+
+    /** Number of distinct values in the dictionary. */
+    pub fn dictionary_len(&self) -> usize { self.dict.len() }
+
+    /** Take ownership, copying the core blocks if they were borrowed. */
+    pub fn take_ownership<'b>(self) -> DictMap<'b,K,V> {
+        DictMap {
+            dict: self.dict,
+            indices: self.indices.take_ownership(),
+            _phantom: PhantomData::default()
+        }
+    }
+}
+
+impl <'a,K,V> Encode for DictMap<'a,K,V> where V: Encode {
+    fn encode<E: Encoder>(&self, encoder: &mut E) -> Result<(), EncodeError> {
+        Encode::encode(DICT_MAGIC, encoder)?;
+        Encode::encode(&self.dict, encoder)?;
+        Encode::encode(&self.indices, encoder)
+    }
+}
+
+impl <'a,'de:'a,K,V> BorrowDecode<'de> for DictMap<'a,K,V>
+    where V: BorrowDecode<'de> + Decode + Encode {
+    fn borrow_decode<D: BorrowDecoder<'de>>(decoder: &mut D) -> Result<Self, DecodeError> {
+        let magic : [u8;4] = Decode::decode(decoder)?;
+        if &magic != DICT_MAGIC {
+            return Err(DecodeError::OtherString("dict magic mismatch".to_string()));
+        }
+        let dict : Vec<V> = BorrowDecode::borrow_decode(decoder)?;
+        let indices : CompressedMap<'a,K,u32> = BorrowDecode::borrow_decode(decoder)?;
+        Ok(DictMap { dict, indices, _phantom: PhantomData::default() })
+    }
+}
+
+impl <'a,K:Hash,V> Index<&K> for DictMap<'a,K,V> {
+    type Output = V;
+    fn index(&self, index: &K) -> &V { self.query(index) }
+}
+
+impl <'a,K:Hash,V> Index<K> for DictMap<'a,K,V> {
+    type Output = V;
+    fn index(&self, index: K) -> &V { self.query(&index) }
+}
+
+/** Utility: vector with bitset selecting which of its elements are iterated over. */
+struct FilteredVec<'a,T> {
+    vec: Vec<(&'a T,Locator)>,
+    filter: BitSet
+}
+
+struct FilteredVecIterator<'a,T> {
+    vec: &'a Vec<(&'a T,Locator)>,
+    bsi: BitSetIterator<'a>
+}
+
+impl <'a,T> Iterator for FilteredVecIterator<'a,T> {
+    type Item = (&'a T, &'a Locator);
+    fn size_hint(&self) -> (usize,Option<usize>) { self.bsi.size_hint() }
+    fn next(&mut self) -> Option<(&'a T, &'a Locator)> {
+        let i = self.bsi.next()?;
+        let (k,v) = &self.vec[i];
+        Some((k,&v))
+    }
+}
+
+impl <'a,T> ExactSizeIterator for FilteredVecIterator<'a,T> {}
 
 impl <'a,'b,T> IntoIterator for &'a FilteredVec<'b,T> {
     type Item = (&'a T, &'a Locator);
@@ -648,7 +1917,7 @@ impl <'a,'b,T> IntoIterator for &'a FilteredVec<'b,T> {
 mod tests {
     use rand::{Rng,SeedableRng};
     use rand::rngs::StdRng;
-    use crate::nonuniform::{CompressedMap,BuildOptions};
+    use crate::nonuniform::{CompressedMap,CascadeSet,ShardedMap,DictMap,BuildOptions};
     use crate::STD_BINCODE_CONFIG;
     use std::collections::HashMap;
     use bincode::{encode_to_vec,decode_from_slice};
@@ -691,4 +1960,304 @@ mod tests {
             assert_eq!(compressed_map, deser.unwrap().0);
         }
     }
+
+    #[test]
+    fn test_cascade_set() {
+        let mut seed = [0u8;32];
+        seed[0] = 42;
+        let mut rng : StdRng = SeedableRng::from_seed(seed);
+
+        /* Disjoint positives, negatives, and a held-out set for the fp rate. */
+        let mut all = std::collections::HashSet::new();
+        let mut draw = |rng:&mut StdRng, n:usize, dst:&mut Vec<u64>, all:&mut std::collections::HashSet<u64>| {
+            while dst.len() < n {
+                let x = rng.gen::<u64>();
+                if all.insert(x) { dst.push(x); }
+            }
+        };
+        let (mut positives, mut negatives, mut strangers) = (Vec::new(),Vec::new(),Vec::new());
+        draw(&mut rng, 500, &mut positives, &mut all);
+        draw(&mut rng, 20000, &mut negatives, &mut all);
+        draw(&mut rng, 20000, &mut strangers, &mut all);
+
+        let mut options = BuildOptions::default();
+        options.key_gen = Some(seed[..16].try_into().unwrap());
+        let fp_rate = 1.0/64.0;
+        let set = CascadeSet::build(&positives, &negatives, fp_rate, &mut options).unwrap();
+
+        /* Zero false negatives, and the known negatives are all rejected. */
+        for p in &positives { assert!(set.contains(p)); }
+        for n in &negatives { assert!(!set.contains(n)); }
+
+        /* Held-out keys collide no more than a few times the target rate. */
+        let fps = strangers.iter().filter(|k| set.contains(*k)).count();
+        assert!((fps as f64) < 4.0 * fp_rate * strangers.len() as f64);
+
+        /* Serialization round-trip. */
+        let ser = encode_to_vec(&set, STD_BINCODE_CONFIG).unwrap();
+        let (deser,_) : (CascadeSet<u64>,usize) = decode_from_slice(&ser, STD_BINCODE_CONFIG).unwrap();
+        assert_eq!(set, deser);
+    }
+
+    #[test]
+    fn test_cascade_set_rejects_negatives_across_seeds() {
+        /* The |N| >> |P| shape lets the positive-ambiguous chain empty while
+         * negatives still linger in the last build set, so the cascade can
+         * land on an odd layer.  One fixed seed can dodge this; sweep several
+         * and insist every known negative is still rejected. */
+        for s in 0u8..16 {
+            let mut seed = [0u8;32];
+            seed[0] = s;
+            let mut rng : StdRng = SeedableRng::from_seed(seed);
+            let mut all = std::collections::HashSet::new();
+            let mut draw = |rng:&mut StdRng, n:usize, dst:&mut Vec<u64>, all:&mut std::collections::HashSet<u64>| {
+                while dst.len() < n {
+                    let x = rng.gen::<u64>();
+                    if all.insert(x) { dst.push(x); }
+                }
+            };
+            let (mut positives, mut negatives) = (Vec::new(),Vec::new());
+            draw(&mut rng, 500, &mut positives, &mut all);
+            draw(&mut rng, 20000, &mut negatives, &mut all);
+
+            let mut options = BuildOptions::default();
+            options.key_gen = Some(seed[..16].try_into().unwrap());
+            let set = CascadeSet::build(&positives, &negatives, 1.0/64.0, &mut options).unwrap();
+
+            /* Odd number of layers: the cascade ends on a positive-derived
+             * (even-index) layer, so no negative survives every layer. */
+            assert_eq!(set.len() % 2, 1, "cascade must end on an even layer (odd layer count)");
+            for p in &positives { assert!(set.contains(p)); }
+            for n in &negatives { assert!(!set.contains(n), "seed {s}: known negative accepted"); }
+        }
+    }
+
+    #[test]
+    fn test_sharded_map() {
+        let mut seed = [0u8;32];
+        seed[0] = 7;
+        let mut rng : StdRng = SeedableRng::from_seed(seed);
+        let mut map = HashMap::new();
+        for _ in 0..5000 {
+            map.insert(rng.gen::<u32>(), rng.gen_range(0u32..4));
+        }
+
+        let mk = |shards:usize| {
+            let mut options = BuildOptions::default();
+            options.key_gen = Some(seed[..16].try_into().unwrap());
+            ShardedMap::build(&map, shards, 4, &mut options).unwrap()
+        };
+
+        /* Shard count must not change the answers for the deterministic seed. */
+        let one = mk(1);
+        let many = mk(7);
+        for (k,v) in &map {
+            assert_eq!(one[k], *v);
+            assert_eq!(many[k], *v);
+        }
+
+        /* Serialization round-trip. */
+        let ser = encode_to_vec(&many, STD_BINCODE_CONFIG).unwrap();
+        let (deser,_) : (ShardedMap<u32,u32>,usize) = decode_from_slice(&ser, STD_BINCODE_CONFIG).unwrap();
+        assert_eq!(many, deser);
+    }
+
+    #[test]
+    fn test_stream_roundtrip() {
+        use std::sync::{Arc,Mutex};
+        use std::io::Write;
+
+        let mut seed = [0u8;32];
+        seed[0] = 99;
+        let mut rng : StdRng = SeedableRng::from_seed(seed);
+        let mut map = HashMap::new();
+        for _ in 0..2000 { map.insert(rng.gen::<u32>(), rng.gen_range(0u32..5)); }
+        let mut options = BuildOptions::default();
+        options.key_gen = Some(seed[..16].try_into().unwrap());
+        let compressed_map = CompressedMap::build(&map, &mut options).unwrap();
+
+        /* write_to then read_from a slice. */
+        let mut buf = Vec::new();
+        compressed_map.write_to(&mut buf).unwrap();
+        let from_stream = CompressedMap::<u32,u32>::read_from(&buf[..]).unwrap();
+        assert_eq!(compressed_map, from_stream);
+
+        /* Same bytes via the background writer. */
+        #[derive(Clone)]
+        struct Sink(Arc<Mutex<Vec<u8>>>);
+        impl Write for Sink {
+            fn write(&mut self, b:&[u8]) -> std::io::Result<usize> {
+                self.0.lock().unwrap().extend_from_slice(b); Ok(b.len())
+            }
+            fn flush(&mut self) -> std::io::Result<()> { Ok(()) }
+        }
+        let sink = Sink(Arc::new(Mutex::new(Vec::new())));
+        compressed_map.write_to_background(sink.clone(), 4).unwrap();
+        assert_eq!(*sink.0.lock().unwrap(), buf);
+    }
+
+    #[test]
+    fn test_dict_map() {
+        let mut seed = [0u8;32];
+        seed[0] = 23;
+        let mut rng : StdRng = SeedableRng::from_seed(seed);
+        let outcomes = ["allow","deny","revoke"];
+        let mut map = HashMap::new();
+        for _ in 0..3000 {
+            map.insert(rng.gen::<u32>(), outcomes[rng.gen_range(0..outcomes.len())].to_string());
+        }
+
+        let mut options = BuildOptions::default();
+        options.key_gen = Some(seed[..16].try_into().unwrap());
+        let dict_map = DictMap::build(&map, &mut options).unwrap();
+        assert_eq!(dict_map.dictionary_len(), 3);
+        for (k,v) in &map { assert_eq!(&dict_map[k], v); }
+
+        let ser = encode_to_vec(&dict_map, STD_BINCODE_CONFIG).unwrap();
+        let (deser,_) : (DictMap<u32,String>,usize) = decode_from_slice(&ser, STD_BINCODE_CONFIG).unwrap();
+        assert_eq!(dict_map, deser);
+    }
+
+    /** A deterministic `u32 -> u32` map with a skewed value distribution. */
+    fn sample_map(seed0:u8, n:usize) -> (HashMap<u32,u32>, BuildOptions) {
+        let mut seed = [0u8;32];
+        seed[0] = seed0;
+        let mut rng : StdRng = SeedableRng::from_seed(seed);
+        let mut map = HashMap::new();
+        while map.len() < n {
+            map.insert(rng.gen::<u32>(), rng.gen_range(0u32..4));
+        }
+        let mut options = BuildOptions::default();
+        options.key_gen = Some(seed[..16].try_into().unwrap());
+        (map, options)
+    }
+
+    #[test]
+    fn test_describe() {
+        let (map, mut options) = sample_map(55, 4000);
+        let compressed_map = CompressedMap::build(&map, &mut options).unwrap();
+        let stats = compressed_map.describe();
+
+        assert!(!stats.phases.is_empty());
+        assert!(stats.intervals.len() >= 2);
+        assert!(stats.entropy > 0.0);
+        assert!(stats.core_bytes > 0);
+        assert!(stats.serialized_bytes >= stats.core_bytes);
+        /* The reported key figure is a capacity upper bound, so it must be at
+         * least the real population. */
+        assert!(stats.estimated_keys >= map.len());
+        assert!(stats.bits_per_key > 0.0);
+        /* Display renders without panicking and mentions the headline figures. */
+        let text = format!("{stats}");
+        assert!(text.contains("bits per key"));
+        assert!(text.contains("upper bound"));
+    }
+
+    #[test]
+    fn test_mmap_from_file() {
+        let (map, mut options) = sample_map(61, 3000);
+        let compressed_map = CompressedMap::build(&map, &mut options).unwrap();
+        let bytes = encode_to_vec(&compressed_map, STD_BINCODE_CONFIG).unwrap();
+
+        let dir = std::env::temp_dir();
+        let good = dir.join(format!("frayed_cascade_mmap_{}.bin", std::process::id()));
+        let trailing = dir.join(format!("frayed_cascade_mmap_{}_trail.bin", std::process::id()));
+        let _ = std::fs::remove_file(&good);
+        let _ = std::fs::remove_file(&trailing);
+
+        std::fs::write(&good, &bytes).unwrap();
+        let mapped = CompressedMap::<u32,u32>::mmap_from_file(&good).unwrap();
+        for (k,v) in &map { assert_eq!(mapped[k], *v); }
+
+        /* Trailing bytes after the encoded map are rejected. */
+        let mut extra = bytes.clone();
+        extra.extend_from_slice(b"junk");
+        std::fs::write(&trailing, &extra).unwrap();
+        assert!(CompressedMap::<u32,u32>::mmap_from_file(&trailing).is_err());
+
+        drop(mapped);
+        let _ = std::fs::remove_file(&good);
+        let _ = std::fs::remove_file(&trailing);
+    }
+
+    #[test]
+    fn test_from_bytes() {
+        let (map, mut options) = sample_map(62, 3000);
+        let compressed_map = CompressedMap::build(&map, &mut options).unwrap();
+        let bytes = encode_to_vec(&compressed_map, STD_BINCODE_CONFIG).unwrap();
+
+        let view = CompressedMap::<u32,u32>::from_bytes(&bytes).unwrap();
+        for (k,v) in &map { assert_eq!(view[k], *v); }
+
+        /* Trailing bytes after the encoded map are rejected. */
+        let mut extra = bytes.clone();
+        extra.push(0);
+        assert!(CompressedMap::<u32,u32>::from_bytes(&extra).is_err());
+    }
+
+    #[test]
+    fn test_checksum_detects_corruption() {
+        let (map, mut options) = sample_map(63, 3000);
+        let compressed_map = CompressedMap::build(&map, &mut options).unwrap();
+        let bytes = encode_to_vec(&compressed_map, STD_BINCODE_CONFIG).unwrap();
+
+        /* Flip the last byte, inside the final core's blocks (past magic + digest). */
+        let mut corrupt = bytes.clone();
+        let pos = corrupt.len() - 1;
+        corrupt[pos] ^= 0x80;
+        let err = decode_from_slice::<CompressedMap<u32,u32>,_>(&corrupt, STD_BINCODE_CONFIG)
+            .err().expect("corrupt body must not decode");
+        assert!(err.to_string().contains("checksum mismatch"));
+    }
+
+    #[test]
+    fn test_cnm1_compat() {
+        let (map, mut options) = sample_map(64, 3000);
+        let compressed_map = CompressedMap::build(&map, &mut options).unwrap();
+        let bytes = encode_to_vec(&compressed_map, STD_BINCODE_CONFIG).unwrap();
+
+        /* Hand-build the legacy `cnm1` container from the `cnm2` bytes: drop the
+         * 8-byte digest and the flags byte, keeping the uncompressed body. */
+        assert_eq!(&bytes[0..4], b"cnm2");
+        assert_eq!(bytes[12], 0, "default build is uncompressed");
+        let mut cnm1 = b"cnm1".to_vec();
+        cnm1.extend_from_slice(&bytes[4+8+1..]);
+
+        let (deser,_) : (CompressedMap<u32,u32>,usize) =
+            decode_from_slice(&cnm1, STD_BINCODE_CONFIG).unwrap();
+        assert_eq!(compressed_map, deser);
+        for (k,v) in &map { assert_eq!(deser[k], *v); }
+    }
+
+    #[cfg(feature = "lz4")]
+    #[test]
+    fn test_compress_values_roundtrip() {
+        /* Redundant, compressible values so the LZ4 frame actually shrinks. */
+        let mut seed = [0u8;32];
+        seed[0] = 71;
+        let mut rng : StdRng = SeedableRng::from_seed(seed);
+        let payload : String = "allow-".repeat(64);
+        let outcomes = [format!("{payload}A"), format!("{payload}B"), format!("{payload}C")];
+        let mut map = HashMap::new();
+        while map.len() < 2000 {
+            map.insert(rng.gen::<u32>(), outcomes[rng.gen_range(0..outcomes.len())].clone());
+        }
+        let mut options = BuildOptions::default();
+        options.key_gen = Some(seed[..16].try_into().unwrap());
+
+        let plain = CompressedMap::build(&map, &mut options).unwrap();
+        let compressed = plain.clone().compress_values(true);
+
+        let plain_bytes = encode_to_vec(&plain, STD_BINCODE_CONFIG).unwrap();
+        let compressed_bytes = encode_to_vec(&compressed, STD_BINCODE_CONFIG).unwrap();
+        assert!(compressed_bytes.len() < plain_bytes.len(),
+            "compression should shrink a redundant value region");
+
+        /* Round-trip the compressed form and check every answer. */
+        let (deser,_) : (CompressedMap<u32,String>,usize) =
+            decode_from_slice(&compressed_bytes, STD_BINCODE_CONFIG).unwrap();
+        for (k,v) in &map { assert_eq!(&deser[k], v); }
+        /* The frame shrank, so the flag survives the round-trip. */
+        assert_eq!(compressed, deser);
+    }
 }
\ No newline at end of file